@@ -0,0 +1,298 @@
+//! Native code generation backend: lowers an `Expr` tree straight to machine
+//! code via Cranelift instead of interpreting `ByteCode`. Selected as an
+//! alternative to the bytecode interpreter for expressions hot enough that
+//! per-op dispatch cost matters.
+//!
+//! This first cut only lowers the numeric fast path (`f64` arithmetic,
+//! comparisons, `?:`, identifiers, and calls to `Single`/`Double`/`Triple`
+//! host functions) - the same scope the interpreter itself started with
+//! before `Value` was introduced. Strings, lists, and maps still have to go
+//! through the interpreter; teaching the JIT the rest of `Value` is natural
+//! follow-up work.
+
+use std::ops::Deref;
+
+use cranelift::prelude::*;
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::environment::{Environment, Function};
+use crate::error::Error;
+use crate::expression::{BinaryExpr, BinaryOperator, CallExpr, ConditionalExpr, Expr, IdentifierExpr, NotExpr};
+use crate::value::Value;
+
+/// A JIT-compiled expression: a function pointer taking the item slice and
+/// returning its `f64` result. Holds the `JITModule` alive for as long as
+/// the function pointer is callable.
+pub(crate) struct CompiledFunction {
+    module: JITModule,
+    entry: extern "C" fn(*const f64, usize) -> f64,
+    // `entry`'s generated code reads `items` at fixed, compile-time byte
+    // offsets (one per `IdentifierExpr` it lowered), with no bounds check of
+    // its own. Recording the item names it was compiled against lets `call`
+    // reject a slice whose length differs from that list instead of the
+    // generated code reading past the end of it; the names themselves are
+    // not compared at call time.
+    item_names: Vec<String>,
+}
+
+impl CompiledFunction {
+    /// Unwraps `items` to the `f64`s the compiled function actually takes.
+    /// The generated code reads every item as an `f64` and `compile` never
+    /// sees item values, so any non-numeric item is rejected here.
+    pub(crate) fn call(&self, items: &[Value]) -> Result<Value, Error> {
+        if items.len() != self.item_names.len() {
+            return Err(Error::new(
+                0,
+                0,
+                &format!("expected {} items, found {}", self.item_names.len(), items.len()),
+            ));
+        }
+
+        let mut numeric = Vec::with_capacity(items.len());
+        for item in items {
+            match item {
+                Value::Num(n) => numeric.push(*n),
+                other => return Err(Error::new(0, 0, &format!("JIT backend only supports numeric items, found {}", other.type_name()))),
+            }
+        }
+
+        Ok(Value::Num((self.entry)(numeric.as_ptr(), numeric.len())))
+    }
+}
+
+impl Drop for CompiledFunction {
+    fn drop(&mut self) {
+        // `entry` points into memory `module` owns; free it explicitly
+        // instead of relying on `JITModule`'s own `Drop` (it doesn't have
+        // one - leaking is its documented behavior) now that nothing will
+        // call `entry` again.
+        let module = std::mem::replace(&mut self.module, empty_module());
+        module.free_memory();
+    }
+}
+
+fn empty_module() -> JITModule {
+    JITModule::new(JITBuilder::new(default_libcall_names()).expect("host ISA lookup"))
+}
+
+/// Compiles `expr` to native code against `env`'s numeric item layout. Only
+/// expressions that evaluate entirely over `f64` (no strings, lists, or
+/// user functions outside `Single`/`Double`/`Triple`) can be compiled;
+/// anything else should fall back to `Expr::emit_bytecode` and the
+/// interpreter.
+pub(crate) fn compile(expr: &dyn Expr, env: &Environment) -> Result<CompiledFunction, Error> {
+    let isa = cranelift_native::builder()
+        .map_err(|msg| Error::new(0, 0, msg))?
+        .finish(settings::Flags::new(settings::builder()))
+        .map_err(|e| Error::new(0, 0, &e.to_string()))?;
+
+    let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
+    // Register every host function this Environment exposes as an extern
+    // symbol up front, so `CallExpr` sites can link straight to it below
+    // instead of going through the interpreter's `Call`/`CallVararg`
+    // dispatch.
+    for (name, func) in env.functions() {
+        let addr = match func {
+            Function::Single(f) => *f as *const u8,
+            Function::Double(f) => *f as *const u8,
+            Function::Triple(f) => *f as *const u8,
+            Function::Vararg(f) => *f as *const u8,
+        };
+        jit_builder.symbol(name, addr);
+    }
+
+    let mut module = JITModule::new(jit_builder);
+
+    let pointer_type = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(pointer_type)); // items: *const f64
+    sig.params.push(AbiParam::new(types::I64)); // items_len: usize
+    sig.returns.push(AbiParam::new(types::F64));
+
+    let func_id = module
+        .declare_function("cel_expr", Linkage::Export, &sig)
+        .map_err(|e| Error::new(0, 0, &e.to_string()))?;
+
+    let mut ctx = module.make_context();
+    ctx.func.signature = sig;
+
+    let mut builder_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+
+    let entry_block = builder.create_block();
+    builder.append_block_params_for_function_params(entry_block);
+    builder.switch_to_block(entry_block);
+    builder.seal_block(entry_block);
+
+    let items_ptr = builder.block_params(entry_block)[0];
+
+    let result = lower(expr, env, &mut module, &mut builder, items_ptr)?;
+    builder.ins().return_(&[result]);
+    builder.finalize();
+
+    module
+        .define_function(func_id, &mut ctx)
+        .map_err(|e| Error::new(0, 0, &e.to_string()))?;
+    module.clear_context(&mut ctx);
+    module
+        .finalize_definitions()
+        .map_err(|e| Error::new(0, 0, &e.to_string()))?;
+
+    let code = module.get_finalized_function(func_id);
+    let entry = unsafe { std::mem::transmute::<_, extern "C" fn(*const f64, usize) -> f64>(code) };
+
+    Ok(CompiledFunction{ module, entry, item_names: env.item_names() })
+}
+
+/// Compiles `expr` and immediately runs it against `items`, discarding the
+/// compiled function afterward. Convenient for one-off evaluation; callers
+/// evaluating the same expression repeatedly should call `compile` once and
+/// reuse the resulting `CompiledFunction` instead.
+pub(crate) fn compile_and_call(expr: &dyn Expr, env: &Environment, items: &[Value]) -> Result<Value, Error> {
+    compile(expr, env)?.call(items)
+}
+
+/// Recursively lowers `expr` into Cranelift IR, dispatching on concrete
+/// `Expr` type the same way `Expr::values_equal` implementations do.
+fn lower(
+    expr: &dyn Expr,
+    env: &Environment,
+    module: &mut JITModule,
+    builder: &mut FunctionBuilder,
+    items_ptr: CraneliftValue,
+) -> Result<CraneliftValue, Error> {
+    if let Some(n) = expr.as_any().downcast_ref::<f64>() {
+        return Ok(builder.ins().f64const(*n));
+    }
+
+    if let Some(v) = expr.as_any().downcast_ref::<Value>() {
+        return match v {
+            Value::Num(n) => Ok(builder.ins().f64const(*n)),
+            _ => Err(Error::new(0, 0, "JIT backend only supports numeric constants")),
+        };
+    }
+
+    if let Some(identifier) = expr.as_any().downcast_ref::<IdentifierExpr>() {
+        let index = env
+            .index_of_item(&identifier.identifier)
+            .ok_or_else(|| Error::new(identifier.line, identifier.column, "identifier not found"))?;
+
+        let offset = (index * std::mem::size_of::<f64>()) as i32;
+        return Ok(builder.ins().load(types::F64, MemFlags::trusted(), items_ptr, offset));
+    }
+
+    if let Some(not_expr) = expr.as_any().downcast_ref::<NotExpr>() {
+        let operand = lower(not_expr.operand.deref(), env, module, builder, items_ptr)?;
+        let zero = builder.ins().f64const(0.0);
+        let is_zero = builder.ins().fcmp(FloatCC::Equal, operand, zero);
+        return Ok(builder.ins().fcvt_from_sint(types::F64, builder.ins().bint(types::I32, is_zero)));
+    }
+
+    if let Some(cond) = expr.as_any().downcast_ref::<ConditionalExpr>() {
+        let condition = lower(cond.condition.deref(), env, module, builder, items_ptr)?;
+        let zero = builder.ins().f64const(0.0);
+        let is_true = builder.ins().fcmp(FloatCC::NotEqual, condition, zero);
+
+        let when_true_block = builder.create_block();
+        let when_false_block = builder.create_block();
+        let done_block = builder.create_block();
+        builder.append_block_param(done_block, types::F64);
+
+        builder.ins().brif(is_true, when_true_block, &[], when_false_block, &[]);
+
+        builder.switch_to_block(when_true_block);
+        builder.seal_block(when_true_block);
+        let true_value = lower(cond.when_true.deref(), env, module, builder, items_ptr)?;
+        builder.ins().jump(done_block, &[true_value]);
+
+        builder.switch_to_block(when_false_block);
+        builder.seal_block(when_false_block);
+        let false_value = lower(cond.when_false.deref(), env, module, builder, items_ptr)?;
+        builder.ins().jump(done_block, &[false_value]);
+
+        builder.switch_to_block(done_block);
+        builder.seal_block(done_block);
+        return Ok(builder.block_params(done_block)[0]);
+    }
+
+    if let Some(binary) = expr.as_any().downcast_ref::<BinaryExpr>() {
+        if binary.operator == BinaryOperator::And || binary.operator == BinaryOperator::Or {
+            return Err(Error::new(
+                binary.line,
+                binary.column,
+                "JIT backend does not support short-circuiting && / ||",
+            ));
+        }
+
+        let left = lower(binary.left.deref(), env, module, builder, items_ptr)?;
+        let right = lower(binary.right.deref(), env, module, builder, items_ptr)?;
+
+        return Ok(match binary.operator {
+            BinaryOperator::Add => builder.ins().fadd(left, right),
+            BinaryOperator::Sub => builder.ins().fsub(left, right),
+            BinaryOperator::Multiply => builder.ins().fmul(left, right),
+            BinaryOperator::Divide => builder.ins().fdiv(left, right),
+            BinaryOperator::Remainder => {
+                return Err(Error::new(binary.line, binary.column, "JIT backend does not support %"));
+            }
+            BinaryOperator::LessThan => bool_as_f64(builder, FloatCC::LessThan, left, right),
+            BinaryOperator::LessEqual => bool_as_f64(builder, FloatCC::LessThanOrEqual, left, right),
+            BinaryOperator::GreaterEqual => bool_as_f64(builder, FloatCC::GreaterThanOrEqual, left, right),
+            BinaryOperator::GreaterThan => bool_as_f64(builder, FloatCC::GreaterThan, left, right),
+            BinaryOperator::Equal => bool_as_f64(builder, FloatCC::Equal, left, right),
+            BinaryOperator::And | BinaryOperator::Or => unreachable!("handled above"),
+        });
+    }
+
+    if let Some(call) = expr.as_any().downcast_ref::<CallExpr>() {
+        let (_, func) = env
+            .function_info(&call.function)
+            .ok_or_else(|| Error::new(call.line, call.column, "function not found"))?;
+
+        let arity = match func {
+            Function::Single(_) => 1,
+            Function::Double(_) => 2,
+            Function::Triple(_) => 3,
+            Function::Vararg(_) => {
+                return Err(Error::new(call.line, call.column, "JIT backend does not support vararg functions"));
+            }
+        };
+
+        if call.arguments.len() != arity {
+            return Err(Error::new(call.line, call.column, "invalid num args"));
+        }
+
+        let mut args = Vec::with_capacity(arity);
+        for arg in &call.arguments {
+            args.push(lower(arg.deref(), env, module, builder, items_ptr)?);
+        }
+
+        // The function's address was registered as an extern symbol of the
+        // same name in `compile`, so it can be linked directly rather than
+        // dispatched through at a known index like the interpreter's `Call`.
+        let mut sig = module.make_signature();
+        for _ in 0..arity {
+            sig.params.push(AbiParam::new(types::F64));
+        }
+        sig.returns.push(AbiParam::new(types::F64));
+
+        let func_id = module
+            .declare_function(&call.function, Linkage::Import, &sig)
+            .map_err(|e| Error::new(call.line, call.column, &e.to_string()))?;
+        let func_ref = module.declare_func_in_func(func_id, builder.func);
+
+        let call_inst = builder.ins().call(func_ref, &args);
+        return Ok(builder.inst_results(call_inst)[0]);
+    }
+
+    Err(Error::new(0, 0, "JIT backend does not support this expression"))
+}
+
+fn bool_as_f64(builder: &mut FunctionBuilder, cc: FloatCC, left: CraneliftValue, right: CraneliftValue) -> CraneliftValue {
+    let cmp = builder.ins().fcmp(cc, left, right);
+    let as_int = builder.ins().bint(types::I32, cmp);
+    builder.ins().fcvt_from_sint(types::F64, as_int)
+}
+
+type CraneliftValue = codegen::ir::Value;
@@ -1,32 +1,95 @@
 use std::any::Any;
 use std::ops::Deref;
 
+use serde::{Deserialize, Serialize};
+
 use crate::environment::{Environment, Function};
 use crate::error::Error;
+use crate::value::Value;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) enum ByteCode {
-    LoadConst(f64),
+    LoadConst(Value),
     LoadItem{ index: usize },
     Call{ func_index: usize, line: usize, column: usize },
     CallVararg{ func_index: usize, num_args: usize, line: usize, column: usize },
-    LessThan,
-    LessEqual,
-    GreaterEqual,
-    GreaterThan,
+    // Comparison and arithmetic ops carry the line/column of the expression
+    // that produced them so the VM can point at the right place when the
+    // operand types don't support the operation.
+    LessThan{ line: usize, column: usize },
+    LessEqual{ line: usize, column: usize },
+    GreaterEqual{ line: usize, column: usize },
+    GreaterThan{ line: usize, column: usize },
     Equal,
-    Add,
-    Sub,
-    Multiply,
-    Divide,
-    Remainder,
-    JumpIfZero{ offset: usize },
+    Add{ line: usize, column: usize },
+    Sub{ line: usize, column: usize },
+    Multiply{ line: usize, column: usize },
+    Divide{ line: usize, column: usize },
+    Remainder{ line: usize, column: usize },
+    /// Pops the evaluated condition of a `?:` and requires it to be exactly
+    /// `Bool` (CEL's truthiness rule - see `Value::truthy`); anything else
+    /// is a runtime error at `line`/`column`. Jumps `offset` instructions
+    /// forward when the condition is `false`.
+    JumpIfFalse{ offset: usize, line: usize, column: usize },
     Jump{ offset: usize},
+    /// Peeks (does not pop) the left operand of `&&`. If it has already
+    /// resolved to `Ok(Bool(false))`, jumps `offset` instructions forward,
+    /// leaving that `false` as the final result and skipping both the right
+    /// operand and `AndCombine`. Otherwise falls through with the left
+    /// operand still on the stack for `AndCombine` to resolve once the right
+    /// operand has also been evaluated.
+    JumpIfFalsy{ offset: usize },
+    /// Mirrors `JumpIfFalsy` for `||`: short-circuits to `true` without
+    /// touching a pending error.
+    JumpIfTruthy{ offset: usize },
+    /// Pops the right operand's result, then the left operand's (left was
+    /// only peeked by `JumpIfFalsy` and is still on the stack). Resolves
+    /// CEL's error-absorbing rule for `&&`: a concrete `false` on either side
+    /// wins even if the other side errored; otherwise the first error
+    /// encountered propagates, else the result is `true`.
+    AndCombine{ line: usize, column: usize },
+    /// Mirrors `AndCombine` for `||`: a concrete `true` on either side wins
+    /// over any error.
+    OrCombine{ line: usize, column: usize },
+    /// Pops the operand and negates it; errors pass through unchanged.
+    Not{ line: usize, column: usize },
+    /// Reads local slot `slot` (e.g. a common sub-expression hoisted by the
+    /// optimizer) and pushes its value.
+    LoadLocal{ slot: usize },
+    /// Pops the top of the stack into local slot `slot`, leaving it on the
+    /// stack as well so the expression that produced it still has its value.
+    StoreLocal{ slot: usize },
+    /// Runs a whole comprehension loop: pops the source list, then runs the
+    /// body (the instructions between this `IterInit` and its matching
+    /// `IterNext`) once per element, with that element bound into
+    /// `loop_var_slot`. Each body result is folded into an accumulator per
+    /// `kind`, starting from `[]` for `map`/`filter`, `true` for `all`,
+    /// `false` for `exists`, and a match count of `0` for `exists_one`
+    /// (which yields `true` only if the count ends at exactly 1). The loop
+    /// stops early once `all`/`exists`/`exists_one` has settled. The final
+    /// result is pushed and execution resumes after the matching `IterNext`.
+    IterInit{ kind: ComprehensionKind, loop_var_slot: usize },
+    /// Marks the end of a comprehension's loop body. `IterInit` runs the
+    /// body itself and resumes after this instruction, so the VM never
+    /// executes `IterNext` directly.
+    IterNext{ kind: ComprehensionKind, loop_var_slot: usize },
+}
+
+/// Which comprehension macro a `ComprehensionExpr` desugars to; determines
+/// how `IterInit` initializes and folds the accumulator.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum ComprehensionKind {
+    Map,
+    Filter,
+    All,
+    Exists,
+    ExistsOne,
 }
 
 pub(crate) trait Expr: core::fmt::Debug {
     fn emit_bytecode(&self, env: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error>;
     fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
     /// Does not compare buffer positions
     fn values_equal(&self, other: &dyn Expr) -> bool;
 }
@@ -35,6 +98,8 @@ pub(crate) type BoxedExpr = Box<dyn Expr>;
 
 #[derive(Debug)]
 pub(crate) struct ConditionalExpr {
+    pub line: usize,
+    pub column: usize,
     pub condition: Box<dyn Expr>,
     pub when_true: Box<dyn Expr>,
     pub when_false: Box<dyn Expr>,
@@ -43,7 +108,7 @@ pub(crate) struct ConditionalExpr {
 impl Expr for ConditionalExpr {
     fn emit_bytecode(&self, env: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
         // Bytecode overview:
-        //   jz false
+        //   jf false
         //   <true logic>
         //   jmp done
         // false:
@@ -59,7 +124,7 @@ impl Expr for ConditionalExpr {
         true_path.push(ByteCode::Jump{ offset: false_path.len() });
 
         self.condition.emit_bytecode(env, bc)?;
-        bc.push(ByteCode::JumpIfZero{ offset: true_path.len() });
+        bc.push(ByteCode::JumpIfFalse{ offset: true_path.len(), line: self.line, column: self.column });
         bc.extend_from_slice(&true_path);
         bc.extend_from_slice(&false_path);
         Ok(())
@@ -69,6 +134,10 @@ impl Expr for ConditionalExpr {
         self
     }
 
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
     fn values_equal(&self, other: &dyn Expr) -> bool {
         other.as_any()
             .downcast_ref::<ConditionalExpr>()
@@ -92,10 +161,14 @@ pub(crate) enum BinaryOperator {
     Multiply,
     Divide,
     Remainder,
+    And,
+    Or,
 }
 
 #[derive(Debug)]
 pub(crate) struct BinaryExpr {
+    pub line: usize,
+    pub column: usize,
     pub left: Box<dyn Expr>,
     pub operator: BinaryOperator,
     pub right: Box<dyn Expr>,
@@ -103,19 +176,48 @@ pub(crate) struct BinaryExpr {
 
 impl Expr for BinaryExpr {
     fn emit_bytecode(&self, env: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
+        let (line, column) = (self.line, self.column);
+
+        // `&&`/`||` must evaluate the left operand first (unlike the other
+        // binary operators below, which emit right-then-left for a trailing
+        // opcode) so they can short-circuit without evaluating the right
+        // operand at all.
+        match self.operator {
+            BinaryOperator::And | BinaryOperator::Or => {
+                let mut right = Vec::new();
+                self.right.emit_bytecode(env, &mut right)?;
+                right.push(if self.operator == BinaryOperator::And {
+                    ByteCode::AndCombine{ line, column }
+                } else {
+                    ByteCode::OrCombine{ line, column }
+                });
+
+                self.left.emit_bytecode(env, bc)?;
+                bc.push(if self.operator == BinaryOperator::And {
+                    ByteCode::JumpIfFalsy{ offset: right.len() }
+                } else {
+                    ByteCode::JumpIfTruthy{ offset: right.len() }
+                });
+                bc.extend_from_slice(&right);
+                return Ok(());
+            }
+            _ => {}
+        }
+
         self.right.emit_bytecode(env, bc)?;
         self.left.emit_bytecode(env, bc)?;
         bc.push(match self.operator {
-            BinaryOperator::LessThan => ByteCode::LessThan, 
-            BinaryOperator::LessEqual => ByteCode::LessEqual,
-            BinaryOperator::GreaterEqual => ByteCode::GreaterEqual,
-            BinaryOperator::GreaterThan => ByteCode::GreaterThan,
+            BinaryOperator::LessThan => ByteCode::LessThan{ line, column },
+            BinaryOperator::LessEqual => ByteCode::LessEqual{ line, column },
+            BinaryOperator::GreaterEqual => ByteCode::GreaterEqual{ line, column },
+            BinaryOperator::GreaterThan => ByteCode::GreaterThan{ line, column },
             BinaryOperator::Equal => ByteCode::Equal,
-            BinaryOperator::Add => ByteCode::Add,
-            BinaryOperator::Sub => ByteCode::Sub,
-            BinaryOperator::Multiply => ByteCode::Multiply,
-            BinaryOperator::Divide => ByteCode::Divide,
-            BinaryOperator::Remainder => ByteCode::Remainder,
+            BinaryOperator::Add => ByteCode::Add{ line, column },
+            BinaryOperator::Sub => ByteCode::Sub{ line, column },
+            BinaryOperator::Multiply => ByteCode::Multiply{ line, column },
+            BinaryOperator::Divide => ByteCode::Divide{ line, column },
+            BinaryOperator::Remainder => ByteCode::Remainder{ line, column },
+            BinaryOperator::And | BinaryOperator::Or => unreachable!("handled above"),
         });
         Ok(())
     }
@@ -124,6 +226,10 @@ impl Expr for BinaryExpr {
         self
     }
 
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
     fn values_equal(&self, other: &dyn Expr) -> bool {
         other.as_any()
             .downcast_ref::<BinaryExpr>()
@@ -181,6 +287,10 @@ impl Expr for CallExpr {
         self
     }
 
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
     fn values_equal(&self, other: &dyn Expr) -> bool {
         other.as_any()
             .downcast_ref::<CallExpr>()
@@ -200,6 +310,90 @@ impl Expr for CallExpr {
     }
 }
 
+#[derive(Debug)]
+pub(crate) struct NotExpr {
+    pub line: usize,
+    pub column: usize,
+    pub operand: Box<dyn Expr>,
+}
+
+impl Expr for NotExpr {
+    fn emit_bytecode(&self, env: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
+        self.operand.emit_bytecode(env, bc)?;
+        bc.push(ByteCode::Not{ line: self.line, column: self.column });
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn values_equal(&self, other: &dyn Expr) -> bool {
+        other.as_any()
+            .downcast_ref::<NotExpr>()
+            .map_or(false, |expr| self.operand.values_equal(expr.operand.deref()))
+    }
+}
+
+/// Desugars a comprehension macro call (`list.map(x, x*2)`,
+/// `list.filter(x, x > 0)`, `list.all(x, ...)`, `list.exists(x, ...)`,
+/// `list.exists_one(x, ...)`) into looping bytecode. Unlike an ordinary
+/// `CallExpr`, the macro's second argument is an expression evaluated once
+/// per element rather than once total, with the loop variable bound into a
+/// fresh local slot - the parser/binder is responsible for recognizing the
+/// macro call form, allocating that slot, and rewriting `IdentifierExpr`s in
+/// `body` that refer to the loop variable into `LocalExpr{ slot }` so they
+/// resolve without going through `Environment::index_of_item`.
+#[derive(Debug)]
+pub(crate) struct ComprehensionExpr {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ComprehensionKind,
+    pub source: Box<dyn Expr>,
+    pub loop_var_slot: usize,
+    pub body: Box<dyn Expr>,
+}
+
+impl Expr for ComprehensionExpr {
+    fn emit_bytecode(&self, env: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
+        self.source.emit_bytecode(env, bc)?;
+        bc.push(ByteCode::IterInit{ kind: self.kind, loop_var_slot: self.loop_var_slot });
+
+        // `IterInit` finds the end of the body by its matching `IterNext`,
+        // so `IterNext` is emitted as the last instruction of the body
+        // itself rather than appended separately after `bc`.
+        let mut body = Vec::new();
+        self.body.emit_bytecode(env, &mut body)?;
+        body.push(ByteCode::IterNext{ kind: self.kind, loop_var_slot: self.loop_var_slot });
+
+        bc.extend_from_slice(&body);
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn values_equal(&self, other: &dyn Expr) -> bool {
+        other.as_any()
+            .downcast_ref::<ComprehensionExpr>()
+            .map_or(false, |expr|
+                self.kind == expr.kind &&
+                self.loop_var_slot == expr.loop_var_slot &&
+                self.source.values_equal(expr.source.deref()) &&
+                self.body.values_equal(expr.body.deref())
+            )
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub(crate) struct IdentifierExpr {
     pub line: usize,
@@ -222,6 +416,10 @@ impl Expr for IdentifierExpr {
         self
     }
 
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
     fn values_equal(&self, other: &dyn Expr) -> bool {
         other.as_any()
             .downcast_ref::<IdentifierExpr>()
@@ -231,7 +429,7 @@ impl Expr for IdentifierExpr {
 
 impl Expr for f64 {
     fn emit_bytecode(&self, _: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
-        bc.push(ByteCode::LoadConst(*self));
+        bc.push(ByteCode::LoadConst(Value::Num(*self)));
         Ok(())
     }
 
@@ -239,9 +437,96 @@ impl Expr for f64 {
         self
     }
 
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
     fn values_equal(&self, other: &dyn Expr) -> bool {
         other.as_any()
             .downcast_ref::<f64>()
             .map_or(false, |val| *val == *self)
     }
 }
+
+impl Expr for Value {
+    fn emit_bytecode(&self, _: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
+        bc.push(ByteCode::LoadConst(self.clone()));
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn values_equal(&self, other: &dyn Expr) -> bool {
+        other.as_any()
+            .downcast_ref::<Value>()
+            .map_or(false, |val| val == self)
+    }
+}
+
+/// References a local slot: either one the optimizer has already hoisted a
+/// value into (see [`HoistExpr`]), or a comprehension's loop variable (see
+/// [`ComprehensionExpr`]).
+#[derive(Debug, PartialEq)]
+pub(crate) struct LocalExpr {
+    pub slot: usize,
+}
+
+impl Expr for LocalExpr {
+    fn emit_bytecode(&self, _: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
+        bc.push(ByteCode::LoadLocal{ slot: self.slot });
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn values_equal(&self, other: &dyn Expr) -> bool {
+        other.as_any()
+            .downcast_ref::<LocalExpr>()
+            .map_or(false, |expr| expr.slot == self.slot)
+    }
+}
+
+/// Wraps a hoisted common sub-expression: emits `value`'s bytecode once,
+/// stores it into `slot`, and leaves it on the stack so the position this
+/// node replaced still evaluates to the same result. Later occurrences of
+/// the same sub-expression are replaced with a plain [`LocalExpr`] that just
+/// reads the slot back.
+#[derive(Debug)]
+pub(crate) struct HoistExpr {
+    pub slot: usize,
+    pub value: Box<dyn Expr>,
+}
+
+impl Expr for HoistExpr {
+    fn emit_bytecode(&self, env: &Environment, bc: &mut Vec<ByteCode>) -> Result<(), Error> {
+        self.value.emit_bytecode(env, bc)?;
+        bc.push(ByteCode::StoreLocal{ slot: self.slot });
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn values_equal(&self, other: &dyn Expr) -> bool {
+        other.as_any()
+            .downcast_ref::<HoistExpr>()
+            .map_or(false, |expr| expr.slot == self.slot && self.value.values_equal(expr.value.deref()))
+    }
+}
@@ -0,0 +1,362 @@
+use std::ops::Deref;
+
+use crate::environment::Environment;
+use crate::expression::{
+    BinaryExpr, BinaryOperator, BoxedExpr, CallExpr, ComprehensionExpr, ComprehensionKind, ConditionalExpr,
+    Expr, HoistExpr, IdentifierExpr, LocalExpr, NotExpr,
+};
+use crate::value::Value;
+
+/// Runs the optimizer over a parsed expression tree before it's handed to
+/// `emit_bytecode`: constant folding, then common sub-expression
+/// elimination. Both passes walk the tree (rather than the emitted
+/// bytecode) so they can use `Expr::values_equal` the same way the parser's
+/// own tree comparisons do.
+pub(crate) fn optimize(expr: BoxedExpr, env: &Environment) -> BoxedExpr {
+    let folded = fold_constants(expr, env);
+    // Comprehensions already have a `loop_var_slot` handed out by the
+    // parser/binder before the optimizer ever runs, and `vm::execute` backs
+    // every slot - CSE's and a comprehension's alike - with one flat
+    // `Vec<Value>` indexed by slot number. Starting CSE's counter past the
+    // highest slot already in use keeps a hoisted local from aliasing a
+    // loop variable (or vice versa), which would otherwise silently
+    // corrupt whichever one gets clobbered last.
+    let mut next_slot = max_loop_var_slot(folded.deref()).map_or(0, |slot| slot + 1);
+    eliminate_common_subexpressions(folded, env, &mut next_slot)
+}
+
+fn placeholder() -> BoxedExpr {
+    Box::new(Value::Null)
+}
+
+/// The highest `loop_var_slot` assigned to any `ComprehensionExpr` anywhere
+/// in `expr`, if there is one.
+fn max_loop_var_slot(expr: &dyn Expr) -> Option<usize> {
+    if let Some(comprehension) = expr.as_any().downcast_ref::<ComprehensionExpr>() {
+        let nested = [comprehension.source.deref(), comprehension.body.deref()]
+            .into_iter()
+            .filter_map(max_loop_var_slot)
+            .max();
+        return Some(nested.map_or(comprehension.loop_var_slot, |n| n.max(comprehension.loop_var_slot)));
+    }
+    if let Some(binary) = expr.as_any().downcast_ref::<BinaryExpr>() {
+        return [binary.left.deref(), binary.right.deref()].into_iter().filter_map(max_loop_var_slot).max();
+    }
+    if let Some(not_expr) = expr.as_any().downcast_ref::<NotExpr>() {
+        return max_loop_var_slot(not_expr.operand.deref());
+    }
+    if let Some(cond) = expr.as_any().downcast_ref::<ConditionalExpr>() {
+        return [cond.condition.deref(), cond.when_true.deref(), cond.when_false.deref()]
+            .into_iter()
+            .filter_map(max_loop_var_slot)
+            .max();
+    }
+    if let Some(call) = expr.as_any().downcast_ref::<CallExpr>() {
+        return call.arguments.iter().filter_map(|arg| max_loop_var_slot(arg.deref())).max();
+    }
+    if let Some(hoist) = expr.as_any().downcast_ref::<HoistExpr>() {
+        return max_loop_var_slot(hoist.value.deref());
+    }
+    None
+}
+
+fn as_constant(expr: &dyn Expr) -> Option<Value> {
+    if let Some(v) = expr.as_any().downcast_ref::<Value>() {
+        return Some(v.clone());
+    }
+    if let Some(v) = expr.as_any().downcast_ref::<f64>() {
+        return Some(Value::Num(*v));
+    }
+    None
+}
+
+fn fold_binary(operator: &BinaryOperator, left: Value, right: Value) -> Option<Value> {
+    match operator {
+        BinaryOperator::Add => left.add(right, 0, 0).ok(),
+        BinaryOperator::Sub => left.sub(right, 0, 0).ok(),
+        BinaryOperator::Multiply => left.multiply(right, 0, 0).ok(),
+        BinaryOperator::Divide => left.divide(right, 0, 0).ok(),
+        BinaryOperator::Remainder => left.remainder(right, 0, 0).ok(),
+        BinaryOperator::Equal => Some(Value::Bool(left.equal(&right))),
+        BinaryOperator::LessThan => left.compare(&right, 0, 0).ok().map(|o| Value::Bool(o.is_lt())),
+        BinaryOperator::LessEqual => left.compare(&right, 0, 0).ok().map(|o| Value::Bool(o.is_le())),
+        BinaryOperator::GreaterEqual => left.compare(&right, 0, 0).ok().map(|o| Value::Bool(o.is_ge())),
+        BinaryOperator::GreaterThan => left.compare(&right, 0, 0).ok().map(|o| Value::Bool(o.is_gt())),
+        // Both operands are already known constants here (not pending
+        // errors), so `&&`/`||`'s error-absorbing semantics don't come into
+        // play - this is a plain two-constant evaluation like the rest.
+        BinaryOperator::And => match (left, right) {
+            (Value::Bool(a), Value::Bool(b)) => Some(Value::Bool(a && b)),
+            _ => None,
+        },
+        BinaryOperator::Or => match (left, right) {
+            (Value::Bool(a), Value::Bool(b)) => Some(Value::Bool(a || b)),
+            _ => None,
+        },
+    }
+}
+
+/// Evaluates a `BinaryExpr`/`ConditionalExpr`/`NotExpr` whose operands are
+/// already known constants, replacing it with a single `Value` leaf. Also
+/// simplifies `cond ? a : b` down to whichever branch the constant
+/// condition takes.
+fn fold_constants(mut expr: BoxedExpr, env: &Environment) -> BoxedExpr {
+    if let Some(binary) = expr.as_any_mut().downcast_mut::<BinaryExpr>() {
+        let left = std::mem::replace(&mut binary.left, placeholder());
+        binary.left = fold_constants(left, env);
+        let right = std::mem::replace(&mut binary.right, placeholder());
+        binary.right = fold_constants(right, env);
+
+        if let (Some(l), Some(r)) = (as_constant(binary.left.deref()), as_constant(binary.right.deref())) {
+            if let Some(folded) = fold_binary(&binary.operator, l, r) {
+                return Box::new(folded);
+            }
+        }
+
+        return expr;
+    }
+
+    if let Some(cond) = expr.as_any_mut().downcast_mut::<ConditionalExpr>() {
+        let condition = std::mem::replace(&mut cond.condition, placeholder());
+        cond.condition = fold_constants(condition, env);
+        let when_true = std::mem::replace(&mut cond.when_true, placeholder());
+        cond.when_true = fold_constants(when_true, env);
+        let when_false = std::mem::replace(&mut cond.when_false, placeholder());
+        cond.when_false = fold_constants(when_false, env);
+
+        if let Some(Value::Bool(taken)) = as_constant(cond.condition.deref()) {
+            return if taken {
+                std::mem::replace(&mut cond.when_true, placeholder())
+            } else {
+                std::mem::replace(&mut cond.when_false, placeholder())
+            };
+        }
+
+        return expr;
+    }
+
+    if let Some(not_expr) = expr.as_any_mut().downcast_mut::<NotExpr>() {
+        let operand = std::mem::replace(&mut not_expr.operand, placeholder());
+        not_expr.operand = fold_constants(operand, env);
+
+        if let Some(Value::Bool(b)) = as_constant(not_expr.operand.deref()) {
+            return Box::new(Value::Bool(!b));
+        }
+
+        return expr;
+    }
+
+    if let Some(call) = expr.as_any_mut().downcast_mut::<CallExpr>() {
+        for arg in call.arguments.iter_mut() {
+            let folded = fold_constants(std::mem::replace(arg, placeholder()), env);
+            *arg = folded;
+        }
+
+        return expr;
+    }
+
+    // The loop body runs once per element, so folding constants inside it
+    // is still safe even though it's evaluated repeatedly - it's the same
+    // constant every time.
+    if let Some(comprehension) = expr.as_any_mut().downcast_mut::<ComprehensionExpr>() {
+        let source = std::mem::replace(&mut comprehension.source, placeholder());
+        comprehension.source = fold_constants(source, env);
+        let body = std::mem::replace(&mut comprehension.body, placeholder());
+        comprehension.body = fold_constants(body, env);
+        return expr;
+    }
+
+    expr
+}
+
+/// Whether `expr` is safe to hoist and evaluate exactly once. Constants and
+/// identifiers always are; calls to user functions are only pure if the
+/// environment says so, since an impure call (e.g. one with side effects or
+/// non-deterministic output) must keep running every time it's reached.
+fn is_pure(expr: &dyn Expr, env: &Environment) -> bool {
+    if expr.as_any().downcast_ref::<Value>().is_some() || expr.as_any().downcast_ref::<f64>().is_some() {
+        return true;
+    }
+    if expr.as_any().downcast_ref::<IdentifierExpr>().is_some() {
+        return true;
+    }
+    if let Some(binary) = expr.as_any().downcast_ref::<BinaryExpr>() {
+        return is_pure(binary.left.deref(), env) && is_pure(binary.right.deref(), env);
+    }
+    if let Some(not_expr) = expr.as_any().downcast_ref::<NotExpr>() {
+        return is_pure(not_expr.operand.deref(), env);
+    }
+    if let Some(cond) = expr.as_any().downcast_ref::<ConditionalExpr>() {
+        return is_pure(cond.condition.deref(), env)
+            && is_pure(cond.when_true.deref(), env)
+            && is_pure(cond.when_false.deref(), env);
+    }
+    if let Some(call) = expr.as_any().downcast_ref::<CallExpr>() {
+        return env.function_is_pure(&call.function)
+            && call.arguments.iter().all(|arg| is_pure(arg.deref(), env));
+    }
+    if let Some(comprehension) = expr.as_any().downcast_ref::<ComprehensionExpr>() {
+        return is_pure(comprehension.source.deref(), env) && is_pure(comprehension.body.deref(), env);
+    }
+    // Both only ever appear already wrapping output of an earlier CSE pass;
+    // `LocalExpr` just reads a slot (no side effect), and `HoistExpr` is as
+    // pure as whatever it wraps.
+    if expr.as_any().downcast_ref::<LocalExpr>().is_some() {
+        return true;
+    }
+    if let Some(hoist) = expr.as_any().downcast_ref::<HoistExpr>() {
+        return is_pure(hoist.value.deref(), env);
+    }
+
+    false
+}
+
+/// Hoists duplicated sub-expressions so their bytecode is emitted and
+/// evaluated once instead of once per occurrence. Only sibling operands of
+/// the same binary expression are checked against each other today (e.g.
+/// `x*x + x*x`) rather than arbitrary pairs anywhere in the tree, since
+/// `Expr` has no generic child-visitor to collect candidates with; widening
+/// this is natural follow-up work once such a visitor exists.
+fn eliminate_common_subexpressions(mut expr: BoxedExpr, env: &Environment, next_slot: &mut usize) -> BoxedExpr {
+    if let Some(binary) = expr.as_any_mut().downcast_mut::<BinaryExpr>() {
+        let left = std::mem::replace(&mut binary.left, placeholder());
+        binary.left = eliminate_common_subexpressions(left, env, next_slot);
+        let right = std::mem::replace(&mut binary.right, placeholder());
+        binary.right = eliminate_common_subexpressions(right, env, next_slot);
+
+        if is_pure(binary.left.deref(), env) && binary.left.values_equal(binary.right.deref()) {
+            let slot = *next_slot;
+            *next_slot += 1;
+
+            // `HoistExpr` (which stores into `slot`) must land on whichever
+            // side `BinaryExpr::emit_bytecode` emits *first*, so the store
+            // always runs before the `LocalExpr` on the other side reads it
+            // back. `&&`/`||` emit left-then-right; every other operator
+            // emits right-then-left (see `BinaryExpr::emit_bytecode`).
+            if matches!(binary.operator, BinaryOperator::And | BinaryOperator::Or) {
+                let hoisted = std::mem::replace(&mut binary.left, placeholder());
+                binary.left = Box::new(HoistExpr{ slot, value: hoisted });
+                binary.right = Box::new(LocalExpr{ slot });
+            } else {
+                let hoisted = std::mem::replace(&mut binary.right, placeholder());
+                binary.right = Box::new(HoistExpr{ slot, value: hoisted });
+                binary.left = Box::new(LocalExpr{ slot });
+            }
+        }
+
+        return expr;
+    }
+
+    if let Some(cond) = expr.as_any_mut().downcast_mut::<ConditionalExpr>() {
+        let condition = std::mem::replace(&mut cond.condition, placeholder());
+        cond.condition = eliminate_common_subexpressions(condition, env, next_slot);
+        let when_true = std::mem::replace(&mut cond.when_true, placeholder());
+        cond.when_true = eliminate_common_subexpressions(when_true, env, next_slot);
+        let when_false = std::mem::replace(&mut cond.when_false, placeholder());
+        cond.when_false = eliminate_common_subexpressions(when_false, env, next_slot);
+        return expr;
+    }
+
+    if let Some(not_expr) = expr.as_any_mut().downcast_mut::<NotExpr>() {
+        let operand = std::mem::replace(&mut not_expr.operand, placeholder());
+        not_expr.operand = eliminate_common_subexpressions(operand, env, next_slot);
+        return expr;
+    }
+
+    if let Some(call) = expr.as_any_mut().downcast_mut::<CallExpr>() {
+        for arg in call.arguments.iter_mut() {
+            let replaced = eliminate_common_subexpressions(std::mem::replace(arg, placeholder()), env, next_slot);
+            *arg = replaced;
+        }
+        return expr;
+    }
+
+    // The loop body re-runs its own bytecode every iteration, so hoisting a
+    // duplicate found inside it is still only one store per iteration, not
+    // one store total - still correct, just scoped to a single pass through
+    // the body rather than the whole comprehension.
+    if let Some(comprehension) = expr.as_any_mut().downcast_mut::<ComprehensionExpr>() {
+        let source = std::mem::replace(&mut comprehension.source, placeholder());
+        comprehension.source = eliminate_common_subexpressions(source, env, next_slot);
+        let body = std::mem::replace(&mut comprehension.body, placeholder());
+        comprehension.body = eliminate_common_subexpressions(body, env, next_slot);
+        return expr;
+    }
+
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `fold_constants`/`eliminate_common_subexpressions`/`is_pure` all take
+    // an `&Environment` (for `function_is_pure` lookups),
+    // but `Environment`'s constructor lives outside this tree's snapshot, so
+    // those three aren't exercisable here. `fold_binary` and `as_constant`
+    // need no such thing and carry the actual folding logic, so they're
+    // covered directly instead.
+
+    #[test]
+    fn folds_arithmetic_on_matching_constants() {
+        assert_eq!(fold_binary(&BinaryOperator::Add, Value::Num(1.0), Value::Num(2.0)), Some(Value::Num(3.0)));
+        assert_eq!(fold_binary(&BinaryOperator::Multiply, Value::Num(2.0), Value::Num(3.0)), Some(Value::Num(6.0)));
+    }
+
+    #[test]
+    fn folds_and_or_only_between_bools() {
+        assert_eq!(fold_binary(&BinaryOperator::And, Value::Bool(true), Value::Bool(false)), Some(Value::Bool(false)));
+        assert_eq!(fold_binary(&BinaryOperator::Or, Value::Bool(true), Value::Bool(false)), Some(Value::Bool(true)));
+        assert_eq!(fold_binary(&BinaryOperator::And, Value::Num(1.0), Value::Bool(false)), None);
+    }
+
+    #[test]
+    fn refuses_to_fold_mismatched_types() {
+        assert_eq!(fold_binary(&BinaryOperator::Add, Value::Num(1.0), Value::Str("x".into())), None);
+    }
+
+    #[test]
+    fn as_constant_recognizes_value_and_legacy_f64_leaves() {
+        assert_eq!(as_constant(&Value::Num(4.0)), Some(Value::Num(4.0)));
+        assert_eq!(as_constant(&3.5_f64), Some(Value::Num(3.5)));
+    }
+
+    fn identifier(name: &str) -> BoxedExpr {
+        Box::new(IdentifierExpr{ line: 0, column: 0, identifier: name.into() })
+    }
+
+    fn duplicated_product(name: &str) -> BoxedExpr {
+        // `x * x`, the shape CSE hoists into a single `HoistExpr`/`LocalExpr`
+        // pair.
+        Box::new(BinaryExpr{
+            line: 0,
+            column: 0,
+            left: identifier(name),
+            operator: BinaryOperator::Multiply,
+            right: identifier(name),
+        })
+    }
+
+    #[test]
+    fn max_loop_var_slot_finds_slot_inside_comprehension_body() {
+        // `list.map(x, x*x)` with `x`'s loop slot already `0` - CSE must
+        // start handing out its own hoist slots at `1`, or the hoisted
+        // `x*x` and the loop variable itself would alias the same vm.rs
+        // local slot.
+        let comprehension = ComprehensionExpr{
+            line: 0,
+            column: 0,
+            kind: ComprehensionKind::Map,
+            source: identifier("list"),
+            loop_var_slot: 0,
+            body: duplicated_product("x"),
+        };
+
+        assert_eq!(max_loop_var_slot(&comprehension), Some(0));
+    }
+
+    #[test]
+    fn max_loop_var_slot_is_none_without_any_comprehension() {
+        assert_eq!(max_loop_var_slot(duplicated_product("x").deref()), None);
+    }
+}
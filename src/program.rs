@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+use crate::environment::Environment;
+use crate::error::Error;
+use crate::expression::ByteCode;
+
+/// A compiled CEL expression, ready to persist across process restarts.
+///
+/// `ByteCode::LoadItem`/`Call`/`CallVararg` address items and functions by
+/// positional index into the `Environment` they were compiled with, so a
+/// `CompiledProgram` also records the ordered names those indices were
+/// resolved against. [`CompiledProgram::load`] re-checks that ordering
+/// before handing back bytecode that's safe to run.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CompiledProgram {
+    bytecode: Vec<ByteCode>,
+    item_names: Vec<String>,
+    function_names: Vec<String>,
+}
+
+impl CompiledProgram {
+    pub(crate) fn new(bytecode: Vec<ByteCode>, env: &Environment) -> Self {
+        CompiledProgram {
+            bytecode,
+            item_names: env.item_names(),
+            function_names: env.function_names(),
+        }
+    }
+
+    /// Validates that `env`'s item/function ordering still matches the
+    /// ordering this program was compiled against. If the environment has
+    /// drifted (items or functions added, removed, or reordered since
+    /// compilation), the indices baked into the bytecode would read or call
+    /// the wrong thing, so this returns an `Error` instead of the bytecode.
+    pub(crate) fn load(self, env: &Environment) -> Result<Vec<ByteCode>, Error> {
+        if self.item_names != env.item_names() {
+            return Err(Error::new(0, 0, "compiled program's items do not match environment"));
+        }
+
+        if self.function_names != env.function_names() {
+            return Err(Error::new(0, 0, "compiled program's functions do not match environment"));
+        }
+
+        Ok(self.bytecode)
+    }
+}
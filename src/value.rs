@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// A runtime CEL value.
+///
+/// Mirrors the shape of cozo's `DataValue`: every value the interpreter
+/// produces or consumes fits into one of these variants, so the arithmetic
+/// and comparison bytecodes can dispatch on type at runtime instead of
+/// assuming everything is an `f64`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Int(i64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Num(_) => "num",
+            Value::Int(_) => "int",
+            Value::Str(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+
+    fn type_error(&self, other: &Value, op: &str, line: usize, column: usize) -> Error {
+        Error::new(
+            line,
+            column,
+            &format!("cannot {} {} and {}", op, self.type_name(), other.type_name()),
+        )
+    }
+
+    pub(crate) fn add(self, other: Value, line: usize, column: usize) -> Result<Value, Error> {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a + b)),
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a + b)),
+            (Value::Str(a), Value::Str(b)) => Ok(Value::Str(a + &b)),
+            (Value::Bytes(a), Value::Bytes(b)) => Ok(Value::Bytes([a, b].concat())),
+            (Value::List(a), Value::List(b)) => Ok(Value::List([a, b].concat())),
+            (a, b) => Err(a.type_error(&b, "add", line, column)),
+        }
+    }
+
+    pub(crate) fn sub(self, other: Value, line: usize, column: usize) -> Result<Value, Error> {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a - b)),
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a - b)),
+            (a, b) => Err(a.type_error(&b, "subtract", line, column)),
+        }
+    }
+
+    pub(crate) fn multiply(self, other: Value, line: usize, column: usize) -> Result<Value, Error> {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a * b)),
+            (Value::Int(a), Value::Int(b)) => Ok(Value::Int(a * b)),
+            (a, b) => Err(a.type_error(&b, "multiply", line, column)),
+        }
+    }
+
+    pub(crate) fn divide(self, other: Value, line: usize, column: usize) -> Result<Value, Error> {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a / b)),
+            (Value::Int(a), Value::Int(b)) => {
+                if b == 0 {
+                    return Err(Error::new(line, column, "division by zero"));
+                }
+                Ok(Value::Int(a / b))
+            }
+            (a, b) => Err(a.type_error(&b, "divide", line, column)),
+        }
+    }
+
+    pub(crate) fn remainder(self, other: Value, line: usize, column: usize) -> Result<Value, Error> {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a % b)),
+            (Value::Int(a), Value::Int(b)) => {
+                if b == 0 {
+                    return Err(Error::new(line, column, "division by zero"));
+                }
+                Ok(Value::Int(a % b))
+            }
+            (a, b) => Err(a.type_error(&b, "take the remainder of", line, column)),
+        }
+    }
+
+    /// Equality works across every type, unlike ordering. Values of
+    /// different, non-numeric types simply compare unequal rather than
+    /// erroring.
+    pub(crate) fn equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Num(a), Value::Int(b)) | (Value::Int(b), Value::Num(a)) => *a == *b as f64,
+            (a, b) => a == b,
+        }
+    }
+
+    /// Ordering only makes sense for comparable types (numbers and
+    /// strings); anything else is a runtime error, surfaced with the line
+    /// and column of the offending comparison.
+    pub(crate) fn compare(&self, other: &Value, line: usize, column: usize) -> Result<Ordering, Error> {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => {
+                a.partial_cmp(b).ok_or_else(|| Error::new(line, column, "cannot compare NaN"))
+            }
+            (Value::Int(a), Value::Int(b)) => Ok(a.cmp(b)),
+            (Value::Num(a), Value::Int(b)) => a
+                .partial_cmp(&(*b as f64))
+                .ok_or_else(|| Error::new(line, column, "cannot compare NaN")),
+            (Value::Int(a), Value::Num(b)) => (*a as f64)
+                .partial_cmp(b)
+                .ok_or_else(|| Error::new(line, column, "cannot compare NaN")),
+            (Value::Str(a), Value::Str(b)) => Ok(a.cmp(b)),
+            (a, b) => Err(a.type_error(b, "compare", line, column)),
+        }
+    }
+
+    /// CEL treats a value as "truthy" only if it is exactly `Bool(true)`;
+    /// anything else (including `Num(1.0)`) is not truthy and is rejected
+    /// with a runtime error at the given position.
+    pub(crate) fn truthy(&self, line: usize, column: usize) -> Result<bool, Error> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            other => Err(Error::new(
+                line,
+                column,
+                &format!("expected bool, found {}", other.type_name()),
+            )),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Num(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_dispatches_on_matching_variants() {
+        assert_eq!(Value::Num(1.0).add(Value::Num(2.0), 0, 0).unwrap(), Value::Num(3.0));
+        assert_eq!(Value::Int(1).add(Value::Int(2), 0, 0).unwrap(), Value::Int(3));
+        assert_eq!(Value::Str("a".into()).add(Value::Str("b".into()), 0, 0).unwrap(), Value::Str("ab".into()));
+    }
+
+    #[test]
+    fn arithmetic_rejects_mismatched_types() {
+        assert!(Value::Num(1.0).add(Value::Str("b".into()), 0, 0).is_err());
+    }
+
+    #[test]
+    fn division_by_zero_errors_for_int_but_not_num() {
+        assert!(Value::Int(1).divide(Value::Int(0), 0, 0).is_err());
+        assert_eq!(Value::Num(1.0).divide(Value::Num(0.0), 0, 0).unwrap(), Value::Num(f64::INFINITY));
+    }
+
+    #[test]
+    fn equal_treats_num_and_int_as_interchangeable() {
+        assert!(Value::Num(1.0).equal(&Value::Int(1)));
+        assert!(!Value::Str("a".into()).equal(&Value::Int(1)));
+    }
+
+    #[test]
+    fn compare_rejects_incomparable_types() {
+        assert!(Value::Str("a".into()).compare(&Value::Num(1.0), 0, 0).is_err());
+        assert!(Value::Num(1.0).compare(&Value::Int(2), 0, 0).unwrap().is_lt());
+    }
+
+    #[test]
+    fn truthy_requires_exactly_bool() {
+        assert!(Value::Bool(true).truthy(0, 0).unwrap());
+        assert!(Value::Num(1.0).truthy(0, 0).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use crate::environment::{Environment, Function};
+use crate::error::Error;
+use crate::expression::{ByteCode, ComprehensionKind};
+use crate::value::Value;
+
+/// Executes compiled bytecode against `items`, the flat slice `LoadItem`
+/// addresses into by index.
+///
+/// The evaluation stack holds `Result<Value, Error>` rather than bare
+/// `Value`. That's what lets `&&`/`||` (`JumpIfFalsy`/`JumpIfTruthy`/
+/// `AndCombine`/`OrCombine`) carry a "pending" error past one operand
+/// without immediately aborting the whole expression - CEL requires
+/// `false && err` to evaluate to `false`, not propagate `err`.
+pub(crate) fn execute(bc: &[ByteCode], items: &[Value], env: &Environment) -> Result<Value, Error> {
+    // Indexed by slot numbers - the optimizer's CSE pass hands those out via
+    // `HoistExpr`/`LocalExpr`, and comprehensions hand out one per loop
+    // variable. Grown lazily as slots are first stored to, and shared across
+    // nested comprehension bodies since their slots are allocated from the
+    // same numbering space.
+    let mut locals: Vec<Value> = Vec::new();
+    run(bc, items, env, &mut locals)
+}
+
+fn run(bc: &[ByteCode], items: &[Value], env: &Environment, locals: &mut Vec<Value>) -> Result<Value, Error> {
+    let mut stack: Vec<Result<Value, Error>> = Vec::new();
+    let mut pc = 0usize;
+
+    while pc < bc.len() {
+        match &bc[pc] {
+            ByteCode::LoadConst(v) => stack.push(Ok(v.clone())),
+
+            ByteCode::LoadItem{ index } => stack.push(Ok(items[*index].clone())),
+
+            ByteCode::LoadLocal{ slot } => stack.push(Ok(locals[*slot].clone())),
+
+            ByteCode::StoreLocal{ slot } => {
+                // Leaves the value on the stack too - the expression that
+                // produced it (the `HoistExpr` side) still needs its value,
+                // same as `StoreLocal`'s doc comment says.
+                let value = pop(&mut stack)?;
+                if *slot >= locals.len() {
+                    locals.resize(*slot + 1, Value::Null);
+                }
+                locals[*slot] = value.clone();
+                stack.push(Ok(value));
+            }
+
+            ByteCode::Call{ func_index, line, column } => {
+                let func = env.function_by_index(*func_index);
+                let result = call_function(func, &mut stack, *line, *column)?;
+                stack.push(result);
+            }
+
+            ByteCode::CallVararg{ func_index, num_args, line, column } => {
+                let func = env.function_by_index(*func_index);
+                let Function::Vararg(f) = func else {
+                    return Err(Error::new(*line, *column, "function is not vararg"));
+                };
+
+                let mut args = Vec::with_capacity(*num_args);
+                for _ in 0..*num_args {
+                    args.push(pop_num(&mut stack, *line, *column)?);
+                }
+
+                stack.push(Ok(Value::Num(f(&args))));
+            }
+
+            ByteCode::LessThan{ line, column } => push_bool(&mut stack, *line, *column, |l, r| Ok(l.compare(&r, *line, *column)?.is_lt()))?,
+            ByteCode::LessEqual{ line, column } => push_bool(&mut stack, *line, *column, |l, r| Ok(l.compare(&r, *line, *column)?.is_le()))?,
+            ByteCode::GreaterEqual{ line, column } => push_bool(&mut stack, *line, *column, |l, r| Ok(l.compare(&r, *line, *column)?.is_ge()))?,
+            ByteCode::GreaterThan{ line, column } => push_bool(&mut stack, *line, *column, |l, r| Ok(l.compare(&r, *line, *column)?.is_gt()))?,
+            ByteCode::Equal => push_bool(&mut stack, 0, 0, |l, r| Ok(l.equal(&r)))?,
+
+            ByteCode::Add{ line, column } => push_binary(&mut stack, *line, *column, |l, r| l.add(r, *line, *column))?,
+            ByteCode::Sub{ line, column } => push_binary(&mut stack, *line, *column, |l, r| l.sub(r, *line, *column))?,
+            ByteCode::Multiply{ line, column } => push_binary(&mut stack, *line, *column, |l, r| l.multiply(r, *line, *column))?,
+            ByteCode::Divide{ line, column } => push_binary(&mut stack, *line, *column, |l, r| l.divide(r, *line, *column))?,
+            ByteCode::Remainder{ line, column } => push_binary(&mut stack, *line, *column, |l, r| l.remainder(r, *line, *column))?,
+
+            ByteCode::JumpIfFalse{ offset, line, column } => {
+                let condition = pop(&mut stack)?.truthy(*line, *column)?;
+                if !condition {
+                    pc += offset;
+                }
+            }
+
+            ByteCode::Jump{ offset } => pc += offset,
+
+            ByteCode::JumpIfFalsy{ offset } => {
+                if matches!(stack.last(), Some(Ok(Value::Bool(false)))) {
+                    pc += offset;
+                }
+            }
+
+            ByteCode::JumpIfTruthy{ offset } => {
+                if matches!(stack.last(), Some(Ok(Value::Bool(true)))) {
+                    pc += offset;
+                }
+            }
+
+            ByteCode::AndCombine{ line, column } => combine_short_circuit(&mut stack, false, *line, *column)?,
+            ByteCode::OrCombine{ line, column } => combine_short_circuit(&mut stack, true, *line, *column)?,
+
+            ByteCode::IterInit{ kind, loop_var_slot } => {
+                let source = pop(&mut stack)?;
+                let Value::List(elements) = source else {
+                    return Err(Error::new(0, 0, &format!("expected list, found {}", source.type_name())));
+                };
+
+                // `IterNext` is always the last instruction of the body this
+                // `IterInit` opened, so the body is exactly the slice between
+                // them; depth-counting skips over any comprehension nested
+                // inside the body instead of stopping at its `IterNext`.
+                let mut depth = 0usize;
+                let mut iter_next_at = None;
+                for (i, op) in bc[pc + 1..].iter().enumerate() {
+                    match op {
+                        ByteCode::IterInit{ .. } => depth += 1,
+                        ByteCode::IterNext{ .. } if depth == 0 => {
+                            iter_next_at = Some(pc + 1 + i);
+                            break;
+                        }
+                        ByteCode::IterNext{ .. } => depth -= 1,
+                        _ => {}
+                    }
+                }
+                let iter_next_at = iter_next_at.expect("IterInit without a matching IterNext");
+                let body = &bc[pc + 1..iter_next_at];
+
+                let mut accumulator = initial_accumulator(*kind);
+                for element in elements {
+                    set_local(locals, *loop_var_slot, element.clone());
+                    let body_result = run(body, items, env, locals)?;
+                    let (next, done) = fold_into(*kind, accumulator, body_result, &element)?;
+                    accumulator = next;
+                    if done {
+                        break;
+                    }
+                }
+
+                stack.push(Ok(finalize(*kind, accumulator)));
+                pc = iter_next_at;
+            }
+
+            ByteCode::IterNext{ .. } => unreachable!("IterInit consumes its own matching IterNext"),
+
+            ByteCode::Not{ line, column } => {
+                let result = pop(&mut stack)?.truthy(*line, *column).map(|b| Value::Bool(!b));
+                stack.push(result);
+            }
+        }
+
+        pc += 1;
+    }
+
+    pop(&mut stack)
+}
+
+fn set_local(locals: &mut Vec<Value>, slot: usize, value: Value) {
+    if slot >= locals.len() {
+        locals.resize(slot + 1, Value::Null);
+    }
+    locals[slot] = value;
+}
+
+fn initial_accumulator(kind: ComprehensionKind) -> Value {
+    match kind {
+        ComprehensionKind::Map | ComprehensionKind::Filter => Value::List(Vec::new()),
+        ComprehensionKind::All => Value::Bool(true),
+        ComprehensionKind::Exists => Value::Bool(false),
+        ComprehensionKind::ExistsOne => Value::Int(0),
+    }
+}
+
+/// Folds one element's body result into `accumulator`. Returns the updated
+/// accumulator plus whether the loop can stop early - `all`/`exists` settle
+/// as soon as one element decides the outcome, and `exists_one` is already
+/// known `false` once a second match is seen.
+fn fold_into(kind: ComprehensionKind, accumulator: Value, body_result: Value, element: &Value) -> Result<(Value, bool), Error> {
+    match kind {
+        ComprehensionKind::Map => {
+            let Value::List(mut list) = accumulator else { unreachable!("Map accumulator is always a List") };
+            list.push(body_result);
+            Ok((Value::List(list), false))
+        }
+        ComprehensionKind::Filter => {
+            let Value::List(mut list) = accumulator else { unreachable!("Filter accumulator is always a List") };
+            if body_result.truthy(0, 0)? {
+                list.push(element.clone());
+            }
+            Ok((Value::List(list), false))
+        }
+        ComprehensionKind::All => {
+            let keep_going = body_result.truthy(0, 0)?;
+            Ok((Value::Bool(keep_going), !keep_going))
+        }
+        ComprehensionKind::Exists => {
+            let found = body_result.truthy(0, 0)?;
+            Ok((Value::Bool(found), found))
+        }
+        ComprehensionKind::ExistsOne => {
+            let Value::Int(count) = accumulator else { unreachable!("ExistsOne accumulator is always an Int") };
+            let count = if body_result.truthy(0, 0)? { count + 1 } else { count };
+            Ok((Value::Int(count), count > 1))
+        }
+    }
+}
+
+fn finalize(kind: ComprehensionKind, accumulator: Value) -> Value {
+    match kind {
+        ComprehensionKind::ExistsOne => {
+            let Value::Int(count) = accumulator else { unreachable!("ExistsOne accumulator is always an Int") };
+            Value::Bool(count == 1)
+        }
+        _ => accumulator,
+    }
+}
+
+fn pop(stack: &mut Vec<Result<Value, Error>>) -> Result<Value, Error> {
+    stack.pop().expect("bytecode popped an empty stack")
+}
+
+fn pop_num(stack: &mut Vec<Result<Value, Error>>, line: usize, column: usize) -> Result<f64, Error> {
+    match pop(stack)? {
+        Value::Num(n) => Ok(n),
+        other => Err(Error::new(line, column, &format!("expected num, found {}", other.type_name()))),
+    }
+}
+
+fn call_function(func: &Function, stack: &mut Vec<Result<Value, Error>>, line: usize, column: usize) -> Result<Result<Value, Error>, Error> {
+    let result = match func {
+        Function::Single(f) => {
+            let a = pop_num(stack, line, column)?;
+            f(a)
+        }
+        Function::Double(f) => {
+            let a = pop_num(stack, line, column)?;
+            let b = pop_num(stack, line, column)?;
+            f(a, b)
+        }
+        Function::Triple(f) => {
+            let a = pop_num(stack, line, column)?;
+            let b = pop_num(stack, line, column)?;
+            let c = pop_num(stack, line, column)?;
+            f(a, b, c)
+        }
+        Function::Vararg(_) => return Err(Error::new(line, column, "vararg function called through Call")),
+    };
+
+    Ok(Ok(Value::Num(result)))
+}
+
+/// Pops left then right (`BinaryExpr::emit_bytecode` emits right then left,
+/// so left is on top) and applies `op(left, right)`, propagating either
+/// side's pending error first.
+fn push_binary(
+    stack: &mut Vec<Result<Value, Error>>,
+    _line: usize,
+    _column: usize,
+    op: impl FnOnce(Value, Value) -> Result<Value, Error>,
+) -> Result<(), Error> {
+    let left = pop(stack)?;
+    let right = pop(stack)?;
+    stack.push(op(left, right));
+    Ok(())
+}
+
+fn push_bool(
+    stack: &mut Vec<Result<Value, Error>>,
+    _line: usize,
+    _column: usize,
+    op: impl FnOnce(Value, Value) -> Result<bool, Error>,
+) -> Result<(), Error> {
+    let left = pop(stack)?;
+    let right = pop(stack)?;
+    stack.push(op(left, right).map(Value::Bool));
+    Ok(())
+}
+
+/// Resolves `&&`/`||`'s error-absorbing rule: `forcing_value` is `false`
+/// for `&&` and `true` for `||`. If either side is a concrete
+/// `forcing_value`, that wins even if the other side errored; otherwise the
+/// first error encountered propagates, else the result is `!forcing_value`.
+fn combine_short_circuit(stack: &mut Vec<Result<Value, Error>>, forcing_value: bool, line: usize, column: usize) -> Result<(), Error> {
+    let right = pop(stack)?;
+    let left = pop(stack)?;
+
+    let result = match (left, right) {
+        (Ok(Value::Bool(l)), _) if l == forcing_value => Ok(Value::Bool(forcing_value)),
+        (_, Ok(Value::Bool(r))) if r == forcing_value => Ok(Value::Bool(forcing_value)),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+        (Ok(Value::Bool(l)), Ok(Value::Bool(r))) => Ok(Value::Bool(if forcing_value { l || r } else { l && r })),
+        // Neither side forced the result and at least one wasn't an error,
+        // so - since two `Bool`s would already have matched above - one of
+        // `l`/`r` is the actual non-bool offender. Name that one, not
+        // whichever side happens to be bound first by the pattern.
+        (Ok(l), Ok(r)) => {
+            let offender = if matches!(l, Value::Bool(_)) { &r } else { &l };
+            Err(Error::new(line, column, &format!("expected bool, found {}", offender.type_name())))
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn err() -> Error {
+        Error::new(0, 0, "boom")
+    }
+
+    #[test]
+    fn and_combine_lets_a_concrete_false_win_over_an_errored_other_side() {
+        // left (bottom) = false, right (top) = error.
+        let mut stack = vec![Ok(Value::Bool(false)), Err(err())];
+        combine_short_circuit(&mut stack, false, 0, 0).unwrap();
+        assert_eq!(stack.pop().unwrap().unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn and_combine_propagates_the_first_error_when_neither_side_forces() {
+        let mut stack = vec![Err(err()), Ok(Value::Bool(true))];
+        combine_short_circuit(&mut stack, false, 0, 0).unwrap();
+        assert!(stack.pop().unwrap().is_err());
+    }
+
+    #[test]
+    fn and_combine_folds_two_bools_normally() {
+        let mut stack = vec![Ok(Value::Bool(true)), Ok(Value::Bool(true))];
+        combine_short_circuit(&mut stack, false, 0, 0).unwrap();
+        assert_eq!(stack.pop().unwrap().unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn and_combine_names_the_actual_non_bool_offender() {
+        // left = true (doesn't force `&&`'s false), right = a non-bool Num -
+        // the error must name the Num, not the (valid) left Bool.
+        let mut stack = vec![Ok(Value::Bool(true)), Ok(Value::Num(1.0))];
+        combine_short_circuit(&mut stack, false, 0, 0).unwrap();
+        let message = format!("{:?}", stack.pop().unwrap().unwrap_err());
+        assert!(message.contains("found num"), "expected message naming the non-bool side, got: {}", message);
+    }
+
+    #[test]
+    fn or_combine_lets_a_concrete_true_win_over_an_errored_other_side() {
+        let mut stack = vec![Ok(Value::Bool(true)), Err(err())];
+        combine_short_circuit(&mut stack, true, 0, 0).unwrap();
+        assert_eq!(stack.pop().unwrap().unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn push_binary_applies_left_then_right_in_emit_order() {
+        // BinaryExpr::emit_bytecode emits right then left, so the stack has
+        // right on bottom and left on top.
+        let mut stack = vec![Ok(Value::Num(2.0)), Ok(Value::Num(5.0))];
+        push_binary(&mut stack, 0, 0, |l, r| l.sub(r, 0, 0)).unwrap();
+        assert_eq!(stack.pop().unwrap().unwrap(), Value::Num(3.0));
+    }
+
+    #[test]
+    fn push_bool_applies_left_then_right() {
+        // Right (2.0) on bottom, left (1.0) on top, as emitted.
+        let mut stack = vec![Ok(Value::Num(2.0)), Ok(Value::Num(1.0))];
+        push_bool(&mut stack, 0, 0, |l, r| Ok(l.compare(&r, 0, 0)?.is_lt())).unwrap();
+        assert_eq!(stack.pop().unwrap().unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn map_appends_every_body_result() {
+        let (acc, done) = fold_into(ComprehensionKind::Map, initial_accumulator(ComprehensionKind::Map), Value::Num(1.0), &Value::Num(0.0)).unwrap();
+        assert!(!done);
+        assert_eq!(acc, Value::List(vec![Value::Num(1.0)]));
+    }
+
+    #[test]
+    fn filter_keeps_the_element_not_the_predicate_result() {
+        let (acc, done) = fold_into(ComprehensionKind::Filter, initial_accumulator(ComprehensionKind::Filter), Value::Bool(true), &Value::Num(42.0)).unwrap();
+        assert!(!done);
+        assert_eq!(acc, Value::List(vec![Value::Num(42.0)]));
+
+        let (acc, _) = fold_into(ComprehensionKind::Filter, acc, Value::Bool(false), &Value::Num(7.0)).unwrap();
+        assert_eq!(acc, Value::List(vec![Value::Num(42.0)]));
+    }
+
+    #[test]
+    fn all_short_circuits_on_the_first_false() {
+        let (acc, done) = fold_into(ComprehensionKind::All, initial_accumulator(ComprehensionKind::All), Value::Bool(false), &Value::Null).unwrap();
+        assert!(done);
+        assert_eq!(finalize(ComprehensionKind::All, acc), Value::Bool(false));
+    }
+
+    #[test]
+    fn exists_short_circuits_on_the_first_true() {
+        let (acc, done) = fold_into(ComprehensionKind::Exists, initial_accumulator(ComprehensionKind::Exists), Value::Bool(true), &Value::Null).unwrap();
+        assert!(done);
+        assert_eq!(finalize(ComprehensionKind::Exists, acc), Value::Bool(true));
+    }
+
+    #[test]
+    fn exists_one_finalizes_true_only_for_exactly_one_match() {
+        let mut acc = initial_accumulator(ComprehensionKind::ExistsOne);
+        for matched in [true, false, false] {
+            let (next, done) = fold_into(ComprehensionKind::ExistsOne, acc, Value::Bool(matched), &Value::Null).unwrap();
+            acc = next;
+            assert!(!done);
+        }
+        assert_eq!(finalize(ComprehensionKind::ExistsOne, acc), Value::Bool(true));
+    }
+
+    #[test]
+    fn exists_one_short_circuits_false_after_a_second_match() {
+        let acc = initial_accumulator(ComprehensionKind::ExistsOne);
+        let (acc, done) = fold_into(ComprehensionKind::ExistsOne, acc, Value::Bool(true), &Value::Null).unwrap();
+        assert!(!done);
+        let (acc, done) = fold_into(ComprehensionKind::ExistsOne, acc, Value::Bool(true), &Value::Null).unwrap();
+        assert!(done);
+        assert_eq!(finalize(ComprehensionKind::ExistsOne, acc), Value::Bool(false));
+    }
+}